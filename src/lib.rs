@@ -5,7 +5,8 @@ use std::{
 };
 
 struct QueueElement<T> {
-    time: Instant,
+    expires_at: Instant,
+    seq: u64,
     value: T,
 }
 
@@ -18,7 +19,7 @@ pub struct QueueStats<T: Ord + Add<Output = T>> {
 
 impl<T> PartialEq for QueueElement<T> {
     fn eq(&self, other: &Self) -> bool {
-        self.time == other.time
+        self.expires_at == other.expires_at
     }
 }
 
@@ -26,7 +27,7 @@ impl<T> Eq for QueueElement<T> {}
 
 impl<T> Ord for QueueElement<T> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        other.time.cmp(&self.time)
+        other.expires_at.cmp(&self.expires_at)
     }
 }
 
@@ -36,13 +37,75 @@ impl<T> PartialOrd for QueueElement<T> {
     }
 }
 
-fn now() -> Instant {
-    Instant::now()
+/// Source of the current time for an `ExpiringQueue`. Swapping in a fake
+/// implementation lets expiry be exercised by advancing virtual time
+/// instead of sleeping the test thread. This crate's own `FakeClock` is
+/// test-only; callers outside this crate who want the same capability
+/// should implement `Clock` themselves and pass it to `with_clock`.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A `Clock` whose time only moves when told to, for deterministic
+/// expiry tests. Cloning shares the same underlying time, so a clone can
+/// be handed to the queue while the original is kept around to call
+/// `advance` on.
+///
+/// This type only exists for this crate's own test suite (it is not
+/// compiled into the published library), since `Cell`/`Rc` aren't
+/// `Send`/`Sync` and a real dependent would want to weigh that tradeoff
+/// itself. Implement `Clock` directly if you need the same thing outside
+/// this crate.
+#[cfg(test)]
+#[derive(Clone)]
+pub struct FakeClock {
+    now: std::rc::Rc<std::cell::Cell<Instant>>,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new() -> FakeClock {
+        FakeClock {
+            now: std::rc::Rc::new(std::cell::Cell::new(Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+#[cfg(test)]
+impl Default for FakeClock {
+    fn default() -> FakeClock {
+        FakeClock::new()
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
 }
 
 pub struct ExpiringQueue<T> {
     heap: BinaryHeap<QueueElement<T>>,
     max_age: Duration,
+    max_len: Option<usize>,
+    max_bytes: Option<usize>,
+    current_size: usize,
+    size_of: Option<fn(&T) -> usize>,
+    clock: Box<dyn Clock>,
+    next_seq: u64,
 }
 
 impl<T> ExpiringQueue<T> {
@@ -50,6 +113,12 @@ impl<T> ExpiringQueue<T> {
         ExpiringQueue {
             heap: BinaryHeap::<QueueElement<T>>::new(),
             max_age: max_age_duration,
+            max_len: None,
+            max_bytes: None,
+            current_size: 0,
+            size_of: None,
+            clock: Box::new(RealClock),
+            next_seq: 0,
         }
     }
 
@@ -57,14 +126,89 @@ impl<T> ExpiringQueue<T> {
         ExpiringQueue {
             heap: BinaryHeap::<QueueElement<T>>::with_capacity(capacity),
             max_age: max_age_duration,
+            max_len: None,
+            max_bytes: None,
+            current_size: 0,
+            size_of: None,
+            clock: Box::new(RealClock),
+            next_seq: 0,
+        }
+    }
+
+    /// Builds a queue bounded both by `max_age` and by a maximum element
+    /// count: once `max_len` is exceeded the oldest-*inserted* element is
+    /// evicted on `push`, so the queue stays bounded regardless of age.
+    /// Insertion order is tracked independently of `push_with_ttl`'s
+    /// per-element expiry, so eviction order doesn't change when TTLs
+    /// are mixed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_len` is zero, since a queue that can never hold an
+    /// element isn't a useful bound.
+    pub fn with_limits(max_age_duration: Duration, max_len: usize) -> ExpiringQueue<T> {
+        assert!(max_len > 0, "max_len must be greater than zero");
+        ExpiringQueue {
+            heap: BinaryHeap::<QueueElement<T>>::new(),
+            max_age: max_age_duration,
+            max_len: Some(max_len),
+            max_bytes: None,
+            current_size: 0,
+            size_of: None,
+            clock: Box::new(RealClock),
+            next_seq: 0,
+        }
+    }
+
+    /// Builds a queue driven by a caller-supplied `Clock`, e.g. a
+    /// `FakeClock` in tests.
+    pub fn with_clock(max_age_duration: Duration, clock: Box<dyn Clock>) -> ExpiringQueue<T> {
+        ExpiringQueue {
+            heap: BinaryHeap::<QueueElement<T>>::new(),
+            max_age: max_age_duration,
+            max_len: None,
+            max_bytes: None,
+            current_size: 0,
+            size_of: None,
+            clock,
+            next_seq: 0,
         }
     }
 
+    /// Pops the raw heap root, if any, keeping `current_size` in sync
+    /// with whatever was evicted. The heap root is the soonest-to-expire
+    /// element, which is what age-based expiry wants.
+    fn pop_raw(&mut self) -> Option<QueueElement<T>> {
+        let popped = self.heap.pop();
+        if let (Some(el), Some(size_of)) = (&popped, self.size_of) {
+            self.current_size -= size_of(&el.value);
+        }
+        popped
+    }
+
+    /// Removes and returns the oldest-*inserted* live element, regardless
+    /// of its expiry. Used by the `max_len`/`max_bytes` bounds, which are
+    /// capacity bounds and so should evict in insertion order even when
+    /// `push_with_ttl` has given elements varied expiries.
+    fn evict_oldest_inserted(&mut self) -> Option<QueueElement<T>> {
+        let mut items: Vec<QueueElement<T>> = std::mem::take(&mut self.heap).into_vec();
+        let oldest_idx = items
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, el)| el.seq)
+            .map(|(idx, _)| idx)?;
+        let removed = items.swap_remove(oldest_idx);
+        self.heap = BinaryHeap::from(items);
+        if let Some(size_of) = self.size_of {
+            self.current_size -= size_of(&removed.value);
+        }
+        Some(removed)
+    }
+
     fn clear_oldest(&mut self, now: Instant) {
         while let Some(el) = self.heap.peek() {
-            let peek_age = now - el.time;
-            if peek_age > self.max_age {
-                self.heap.pop();
+            if now > el.expires_at {
+                self.pop_raw();
             } else {
                 break;
             }
@@ -72,18 +216,48 @@ impl<T> ExpiringQueue<T> {
     }
 
     pub fn push(&mut self, value: T) -> usize {
-        let now = now();
+        self.push_with_ttl(value, self.max_age)
+    }
+
+    /// Pushes `value` with its own expiry instead of the queue's default
+    /// `max_age`, so individual elements can outlive or expire sooner
+    /// than the rest of the window.
+    pub fn push_with_ttl(&mut self, value: T, ttl: Duration) -> usize {
+        let now = self.clock.now();
         self.clear_oldest(now);
-        self.heap.push(QueueElement { time: now, value });
+        if let Some(max_len) = self.max_len {
+            while self.heap.len() >= max_len {
+                self.evict_oldest_inserted();
+            }
+        }
+        if let Some(size_of) = self.size_of {
+            self.current_size += size_of(&value);
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.heap.push(QueueElement {
+            expires_at: now + ttl,
+            seq,
+            value,
+        });
+        if let Some(max_bytes) = self.max_bytes {
+            while self.current_size > max_bytes {
+                if self.evict_oldest_inserted().is_none() {
+                    break;
+                }
+            }
+        }
         self.heap.len()
     }
 
     pub fn clear(&mut self) {
         self.heap.clear();
+        self.current_size = 0;
     }
 
     pub fn len(&mut self) -> usize {
-        self.clear_oldest(now());
+        let now = self.clock.now();
+        self.clear_oldest(now);
         self.heap.len()
     }
 
@@ -99,14 +273,126 @@ impl<T> ExpiringQueue<T> {
         self.max_age
     }
 
+    pub fn max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    pub fn max_bytes(&self) -> Option<usize> {
+        self.max_bytes
+    }
+
+    pub fn byte_size(&self) -> usize {
+        self.current_size
+    }
+
     pub fn peek(&mut self) -> Option<&T> {
-        self.clear_oldest(now());
+        let now = self.clock.now();
+        self.clear_oldest(now);
         self.heap.peek().map(|q_element| &q_element.value)
     }
 
     pub fn pop(&mut self) -> Option<T> {
-        self.clear_oldest(now());
-        self.heap.pop().map(|q_element| q_element.value)
+        let now = self.clock.now();
+        self.clear_oldest(now);
+        self.pop_raw().map(|q_element| q_element.value)
+    }
+
+    /// Yields references to all currently-live values without consuming
+    /// them, unlike repeated `pop`.
+    pub fn iter(&mut self) -> impl Iterator<Item = &T> {
+        let now = self.clock.now();
+        self.clear_oldest(now);
+        self.heap.iter().map(|q_element| &q_element.value)
+    }
+
+    /// Pops and returns every element that has already expired, so
+    /// callers can observe and handle evicted items (e.g. flush-on-expire)
+    /// instead of having them silently dropped by the next `clear_oldest`.
+    pub fn drain_expired(&mut self) -> Vec<T> {
+        let now = self.clock.now();
+        let mut expired = Vec::new();
+        while let Some(el) = self.heap.peek() {
+            if now > el.expires_at {
+                if let Some(popped) = self.pop_raw() {
+                    expired.push(popped.value);
+                }
+            } else {
+                break;
+            }
+        }
+        expired
+    }
+
+    /// Drops expired entries, then keeps only the live elements for which
+    /// `f` returns true, rebuilding the heap from the filtered set. Each
+    /// retained element keeps its original expiry rather than having it
+    /// reset, unlike draining and re-pushing everything would.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) {
+        let now = self.clock.now();
+        self.clear_oldest(now);
+        let size_of = self.size_of;
+        for el in self.heap.drain().collect::<Vec<_>>() {
+            if f(&el.value) {
+                self.heap.push(el);
+            } else if let Some(size_of) = size_of {
+                self.current_size -= size_of(&el.value);
+            }
+        }
+    }
+}
+
+/// In-memory byte size of a value, used to bound an `ExpiringQueue` by
+/// total payload size rather than element count.
+pub trait SizeOf {
+    fn size_of(&self) -> usize;
+}
+
+impl<T: SizeOf> ExpiringQueue<T> {
+    /// Builds a queue bounded both by `max_age` and by a total byte
+    /// budget: once `max_bytes` is exceeded the oldest-*inserted*
+    /// elements are evicted on `push` until the queue fits again.
+    /// Insertion order is tracked independently of `push_with_ttl`'s
+    /// per-element expiry, so eviction order doesn't change when TTLs
+    /// are mixed.
+    pub fn with_byte_limit(max_age_duration: Duration, max_bytes: usize) -> ExpiringQueue<T> {
+        ExpiringQueue {
+            heap: BinaryHeap::<QueueElement<T>>::new(),
+            max_age: max_age_duration,
+            max_len: None,
+            max_bytes: Some(max_bytes),
+            current_size: 0,
+            size_of: Some(T::size_of),
+            clock: Box::new(RealClock),
+            next_seq: 0,
+        }
+    }
+}
+
+impl<T: Ord + Clone + Add<Output = T>> ExpiringQueue<T> {
+    pub fn stats(&mut self) -> QueueStats<T> {
+        let now = self.clock.now();
+        self.clear_oldest(now);
+        let len = self.heap.len();
+        let mut values = self.heap.iter().map(|q_element| &q_element.value);
+        let (min, max, sum) = match values.next() {
+            Some(first) => {
+                let mut min = first.clone();
+                let mut max = first.clone();
+                let mut sum = first.clone();
+                for value in values {
+                    if value < &min {
+                        min = value.clone();
+                    }
+                    if value > &max {
+                        max = value.clone();
+                    }
+                    sum = sum + value.clone();
+                }
+                (Some(min), Some(max), Some(sum))
+            }
+            None => (None, None, None),
+        };
+        QueueStats { min, max, sum, len }
     }
 }
 
@@ -114,21 +400,181 @@ impl<T> ExpiringQueue<T> {
 mod tests {
     use std::time::Duration;
 
-    use crate::ExpiringQueue;
+    use crate::{ExpiringQueue, FakeClock, SizeOf};
+
+    #[derive(Debug, PartialEq, Clone)]
+    struct Packet(usize, &'static str);
+
+    impl SizeOf for Packet {
+        fn size_of(&self) -> usize {
+            self.0
+        }
+    }
 
     #[test]
     fn is_empty_test() {
-        let mut queue: ExpiringQueue<i32> = ExpiringQueue::new(Duration::from_secs(5));
+        let clock = FakeClock::new();
+        let mut queue: ExpiringQueue<i32> =
+            ExpiringQueue::with_clock(Duration::from_secs(5), Box::new(clock.clone()));
         queue.push(2);
         assert_eq!(queue.len(), 1);
-        sleep_secs(5);
+        clock.advance(Duration::from_secs(5) + Duration::from_millis(1));
         assert_eq!(queue.len(), 0);
         assert_eq!(queue.is_empty(), true);
     }
 
-    #[cfg(test)]
-    fn sleep_secs(dur_secs: u64) {
-        println!("\nSleeping {} secs ...", dur_secs);
-        std::thread::sleep(Duration::from_secs(dur_secs));
+    #[test]
+    fn with_limits_evicts_oldest_once_over_capacity() {
+        let mut queue: ExpiringQueue<i32> = ExpiringQueue::with_limits(Duration::from_secs(60), 2);
+        queue.push(1);
+        queue.push(2);
+        queue.push(3);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn with_limits_evicts_oldest_inserted_even_with_a_longer_ttl() {
+        let mut queue: ExpiringQueue<&str> = ExpiringQueue::with_limits(Duration::from_secs(60), 2);
+        queue.push_with_ttl("A-old-long-ttl", Duration::from_secs(100));
+        queue.push_with_ttl("B-new-short-ttl", Duration::from_secs(1));
+        queue.push_with_ttl("C-new-long-ttl", Duration::from_secs(100));
+        let mut values: Vec<&str> = queue.iter().copied().collect();
+        values.sort();
+        assert_eq!(values, vec!["B-new-short-ttl", "C-new-long-ttl"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "max_len must be greater than zero")]
+    fn with_limits_rejects_zero_max_len() {
+        let _queue: ExpiringQueue<i32> = ExpiringQueue::with_limits(Duration::from_secs(5), 0);
+    }
+
+    #[test]
+    fn stats_reports_min_max_sum_and_len() {
+        let clock = FakeClock::new();
+        let mut queue: ExpiringQueue<i32> =
+            ExpiringQueue::with_clock(Duration::from_secs(5), Box::new(clock));
+        queue.push(3);
+        queue.push(1);
+        queue.push(4);
+        let stats = queue.stats();
+        assert_eq!(stats.min, Some(1));
+        assert_eq!(stats.max, Some(4));
+        assert_eq!(stats.sum, Some(8));
+        assert_eq!(stats.len, 3);
+    }
+
+    #[test]
+    fn stats_on_empty_queue_is_all_none() {
+        let clock = FakeClock::new();
+        let mut queue: ExpiringQueue<i32> =
+            ExpiringQueue::with_clock(Duration::from_secs(5), Box::new(clock));
+        let stats = queue.stats();
+        assert_eq!(stats.min, None);
+        assert_eq!(stats.max, None);
+        assert_eq!(stats.sum, None);
+        assert_eq!(stats.len, 0);
+    }
+
+    #[test]
+    fn with_byte_limit_evicts_oldest_to_stay_under_budget() {
+        let mut queue: ExpiringQueue<Packet> =
+            ExpiringQueue::with_byte_limit(Duration::from_secs(60), 10);
+        queue.push(Packet(4, "a"));
+        queue.push(Packet(4, "b"));
+        assert_eq!(queue.byte_size(), 8);
+        queue.push(Packet(4, "c"));
+        assert_eq!(queue.byte_size(), 8);
+        assert_eq!(queue.len(), 2);
+    }
+
+    #[test]
+    fn with_byte_limit_evicts_a_single_element_larger_than_the_budget() {
+        let mut queue: ExpiringQueue<Packet> =
+            ExpiringQueue::with_byte_limit(Duration::from_secs(60), 10);
+        queue.push(Packet(20, "a"));
+        assert_eq!(queue.len(), 0);
+        assert_eq!(queue.byte_size(), 0);
+    }
+
+    #[test]
+    fn with_byte_limit_evicts_oldest_inserted_even_with_a_longer_ttl() {
+        let mut queue: ExpiringQueue<Packet> =
+            ExpiringQueue::with_byte_limit(Duration::from_secs(60), 10);
+        queue.push_with_ttl(Packet(4, "A-old-long-ttl"), Duration::from_secs(100));
+        queue.push_with_ttl(Packet(4, "B-new-short-ttl"), Duration::from_secs(1));
+        queue.push_with_ttl(Packet(4, "C-new-long-ttl"), Duration::from_secs(100));
+        let mut labels: Vec<&str> = queue.iter().map(|packet| packet.1).collect();
+        labels.sort();
+        assert_eq!(labels, vec!["B-new-short-ttl", "C-new-long-ttl"]);
+    }
+
+    #[test]
+    fn push_with_ttl_overrides_default_max_age() {
+        let clock = FakeClock::new();
+        let mut queue: ExpiringQueue<i32> =
+            ExpiringQueue::with_clock(Duration::from_secs(5), Box::new(clock.clone()));
+        queue.push_with_ttl(1, Duration::from_secs(1));
+        queue.push_with_ttl(2, Duration::from_secs(10));
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.pop(), Some(2));
+    }
+
+    #[test]
+    fn iter_yields_live_values_without_consuming() {
+        let clock = FakeClock::new();
+        let mut queue: ExpiringQueue<i32> =
+            ExpiringQueue::with_clock(Duration::from_secs(5), Box::new(clock));
+        queue.push(1);
+        queue.push(2);
+        queue.push(3);
+        let mut values: Vec<i32> = queue.iter().copied().collect();
+        values.sort();
+        assert_eq!(values, vec![1, 2, 3]);
+        assert_eq!(queue.len(), 3);
+    }
+
+    #[test]
+    fn drain_expired_returns_only_expired_elements() {
+        let clock = FakeClock::new();
+        let mut queue: ExpiringQueue<i32> =
+            ExpiringQueue::with_clock(Duration::from_secs(5), Box::new(clock.clone()));
+        queue.push_with_ttl(1, Duration::from_secs(1));
+        queue.push_with_ttl(2, Duration::from_secs(10));
+        clock.advance(Duration::from_secs(2));
+        let expired = queue.drain_expired();
+        assert_eq!(expired, vec![1]);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.pop(), Some(2));
+    }
+
+    #[test]
+    fn retain_keeps_only_matching_live_elements() {
+        let clock = FakeClock::new();
+        let mut queue: ExpiringQueue<i32> =
+            ExpiringQueue::with_clock(Duration::from_secs(5), Box::new(clock));
+        queue.push(1);
+        queue.push(2);
+        queue.push(3);
+        queue.push(4);
+        queue.retain(|v| v % 2 == 0);
+        let mut values: Vec<i32> = queue.iter().copied().collect();
+        values.sort();
+        assert_eq!(values, vec![2, 4]);
+    }
+
+    #[test]
+    fn retain_preserves_original_expiry_instead_of_resetting_it() {
+        let clock = FakeClock::new();
+        let mut queue: ExpiringQueue<i32> =
+            ExpiringQueue::with_clock(Duration::from_secs(5), Box::new(clock.clone()));
+        queue.push_with_ttl(1, Duration::from_secs(2));
+        queue.retain(|_| true);
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(queue.len(), 0);
     }
 }